@@ -0,0 +1,122 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Settings an installer can drop onto the device instead of memorizing the
+/// positional CLI argument order. Every field is optional: `port`, `baud`,
+/// `output_h` and `output_v` are overridden by the matching CLI argument when
+/// one is given, the centering fields come only from the file, and unset
+/// fields fall back to the binary's own defaults.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScalerConfig {
+    pub port: Option<String>,
+    pub baud: Option<u32>,
+    pub output_h: Option<u32>,
+    pub output_v: Option<u32>,
+    pub center_base: Option<u32>,
+    pub h_offset: Option<i32>,
+    pub v_offset: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    key: String,
+    value: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid value {:?} for '{}'", self.value, self.key)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Parses a newline-delimited `key=value` config file. Blank lines and lines
+/// starting with `#` are ignored; unknown keys are logged and skipped so
+/// newer installer configs still load on older binaries.
+pub fn parse(contents: &str) -> Result<ScalerConfig, ConfigError> {
+    let mut config = ScalerConfig::default();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some(parts) => parts,
+            None => {
+                eprintln!("Ignoring malformed config line: {:?}", line);
+                continue;
+            }
+        };
+        let key = key.trim();
+        let value = value.trim();
+        let invalid = || ConfigError {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        match key {
+            "port" => config.port = Some(value.to_string()),
+            "baud" => config.baud = Some(value.parse().map_err(|_| invalid())?),
+            "output_h" => config.output_h = Some(value.parse().map_err(|_| invalid())?),
+            "output_v" => config.output_v = Some(value.parse().map_err(|_| invalid())?),
+            "center_base" => config.center_base = Some(value.parse().map_err(|_| invalid())?),
+            "h_offset" => config.h_offset = Some(value.parse().map_err(|_| invalid())?),
+            "v_offset" => config.v_offset = Some(value.parse().map_err(|_| invalid())?),
+            _ => eprintln!("Ignoring unknown config key: {:?}", key),
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_keys() {
+        let config = parse(
+            "port=/dev/ttyUSB0\n\
+             baud=9600\n\
+             output_h=1920\n\
+             output_v=1080\n\
+             center_base=10240\n\
+             h_offset=-4\n\
+             v_offset=2\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            ScalerConfig {
+                port: Some("/dev/ttyUSB0".to_string()),
+                baud: Some(9600),
+                output_h: Some(1920),
+                output_v: Some(1080),
+                center_base: Some(10240),
+                h_offset: Some(-4),
+                v_offset: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn ignores_blank_lines_and_comments() {
+        let config = parse("\n# a comment\nport=/dev/ttyUSB0\n   \n").unwrap();
+        assert_eq!(config.port, Some("/dev/ttyUSB0".to_string()));
+    }
+
+    #[test]
+    fn ignores_unknown_keys() {
+        let config = parse("mac=00:11:22:33:44:55\nport=/dev/ttyUSB0\n").unwrap();
+        assert_eq!(config.port, Some("/dev/ttyUSB0".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_numeric_value() {
+        let err = parse("baud=fast\n").unwrap_err();
+        assert_eq!(err.to_string(), "invalid value \"fast\" for 'baud'");
+    }
+}
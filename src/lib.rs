@@ -0,0 +1,927 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+pub const ESC: &str = "\x1b";
+
+/// How long `run` waits for an unsolicited push (e.g. `Reconfig`) from the
+/// device before looping back around to check for other work.
+const IDLE_POLL_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long `send_command_with_response` waits for a matching reply to a
+/// single command attempt before retrying.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default value the Extron SIS protocol treats as "zero" for `HCTR`/`VCTR`.
+pub const DEFAULT_CENTER_BASE: u32 = 10240;
+
+pub mod config;
+pub mod protocol;
+
+use protocol::ScalerProtocol;
+
+/// Abstracts the byte-level connection to the scaler so the state machine can
+/// be driven by a scripted transport in tests instead of a real serial port.
+pub trait ScalerTransport {
+    /// Waits up to `timeout` for a full line, returning it with the line
+    /// ending stripped. Returns `Ok(None)` if nothing arrives in time.
+    fn read_line(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>>;
+
+    /// Writes a raw ESC command to the device.
+    fn write_command(&mut self, cmd: &str) -> io::Result<()>;
+}
+
+/// Wraps a real serial port, holding the bytes of a line still in progress
+/// across `read_line` calls. `IDLE_POLL_TIMEOUT` is shorter than a full line
+/// at the low baud rates this tool supports (e.g. `Reconfig\n` at 1200 baud),
+/// so a deadline firing mid-line is routine, not exceptional: the partial
+/// bytes have already been pulled off the wire and must be resumed on the
+/// next call rather than dropped, or the line boundary desyncs permanently.
+pub struct SerialTransport {
+    port: Box<dyn serialport::SerialPort>,
+    buf: Vec<u8>,
+}
+
+impl SerialTransport {
+    pub fn new(port: Box<dyn serialport::SerialPort>) -> Self {
+        Self { port, buf: Vec::new() }
+    }
+}
+
+impl ScalerTransport for SerialTransport {
+    fn read_line(&mut self, timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        let mut byte = [0u8; 1];
+        loop {
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            match self.port.read_exact(&mut byte) {
+                Ok(()) => {
+                    if byte[0] == b'\n' {
+                        return Ok(Some(std::mem::take(&mut self.buf)));
+                    }
+                    if byte[0] != 0 && byte[0] != b'\r' {
+                        self.buf.push(byte[0]);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn write_command(&mut self, cmd: &str) -> io::Result<()> {
+        self.port.write_all(cmd.as_bytes())
+    }
+}
+
+/// Tunables for the command/response link. `max_retries` and `poll_interval`
+/// are surfaced as CLI args in `main`; the timeouts use fixed defaults.
+#[derive(Debug, Copy, Clone)]
+pub struct LinkOptions {
+    pub idle_timeout: Duration,
+    pub response_timeout: Duration,
+    pub max_retries: u32,
+    /// How often to re-poll `APIX`/`ALIN` while idle to catch a source
+    /// changing resolution without emitting a `Reconfig`. `None` disables
+    /// the keepalive poll entirely.
+    pub poll_interval: Option<Duration>,
+}
+
+impl LinkOptions {
+    pub fn new(max_retries: u32, poll_interval: Option<Duration>) -> Self {
+        Self {
+            idle_timeout: IDLE_POLL_TIMEOUT,
+            response_timeout: RESPONSE_TIMEOUT,
+            max_retries,
+            poll_interval,
+        }
+    }
+}
+
+/// Failure modes of a single request/response exchange with the scaler.
+#[derive(Debug)]
+pub enum CommandError {
+    Io(io::Error),
+    Timeout(String),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Io(e) => write!(f, "I/O error: {}", e),
+            CommandError::Timeout(cmd) => {
+                write!(f, "Timed out waiting for a response to {:?}", cmd)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[derive(Debug)]
+pub struct State {
+    pub step: CommandFlow,
+    pub input_size: Resolution,
+    pub output_size: Resolution,
+    /// When the idle keepalive poll (see `LinkOptions::poll_interval`) last
+    /// ran, so `drive_once` knows when it's due again.
+    pub last_poll: Option<Instant>,
+    /// Base value `HCTR`/`VCTR` are offset from; the Extron SIS protocol
+    /// treats this as its "zero" for centering. Configurable per `config::ScalerConfig`.
+    pub center_base: u32,
+    /// Fixed nudge applied on top of the computed horizontal center, for
+    /// sources that need a manual trim. Configurable per `config::ScalerConfig`.
+    pub h_offset: i32,
+    /// Fixed nudge applied on top of the computed vertical center, for
+    /// sources that need a manual trim. Configurable per `config::ScalerConfig`.
+    pub v_offset: i32,
+    /// How the input is scaled to the output before centering.
+    pub scale_mode: ScaleMode,
+}
+
+impl State {
+    pub fn set_output_size(&mut self, r: Resolution) {
+        self.output_size = r
+    }
+
+    pub fn reset(&mut self) {
+        self.step = CommandFlow::Uninitialized;
+        self.input_size = Resolution { h: 0, v: 0 };
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            step: CommandFlow::Uninitialized,
+            input_size: Resolution { h: 0, v: 0 },
+            output_size: Resolution { h: 0, v: 0 },
+            last_poll: None,
+            center_base: DEFAULT_CENTER_BASE,
+            h_offset: 0,
+            v_offset: 0,
+            scale_mode: ScaleMode::default(),
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Step: {:?}, Input size: Hor {}, Ver {}, Output size: Hor {}, Ver {}",
+            self.step, self.input_size.h, self.input_size.v, self.output_size.h, self.output_size.v,
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandFlow {
+    Uninitialized,
+    Reconfig,
+    GotHorizontalSize,
+    GotVerticalSize,
+    SetHSize,
+    SetVSize,
+    SetHCenter,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Resolution {
+    pub h: u32,
+    pub v: u32,
+}
+
+/// How the input is scaled to fit the output before centering.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ScaleMode {
+    /// 1:1 passthrough, just centered (today's behavior).
+    #[default]
+    Center,
+    /// Scale so the whole input fits inside the output, preserving aspect
+    /// ratio; may letterbox.
+    Fit,
+    /// Scale so the output is fully covered, preserving aspect ratio; the
+    /// input may overflow the output.
+    Fill,
+}
+
+impl std::str::FromStr for ScaleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "center" => Ok(ScaleMode::Center),
+            "fit" => Ok(ScaleMode::Fit),
+            "fill" => Ok(ScaleMode::Fill),
+            other => Err(format!("Unknown scale mode '{}'", other)),
+        }
+    }
+}
+
+/// Scales `input` to fit/fill `output` per `mode`, preserving aspect ratio
+/// for `Fit`/`Fill`. Returns `input` unchanged for `Center`, or if either
+/// dimension of `input` is zero (nothing to scale yet). Shared by any
+/// `ScalerProtocol` that wants this behavior, since it isn't tied to a
+/// specific command dialect.
+pub(crate) fn scaled_size(mode: ScaleMode, output: Resolution, input: Resolution) -> Resolution {
+    if mode == ScaleMode::Center || input.h == 0 || input.v == 0 {
+        return input;
+    }
+
+    let ratio_h = output.h as f64 / input.h as f64;
+    let ratio_v = output.v as f64 / input.v as f64;
+    let ratio = match mode {
+        ScaleMode::Fit => ratio_h.min(ratio_v),
+        ScaleMode::Fill => ratio_h.max(ratio_v),
+        ScaleMode::Center => unreachable!(),
+    };
+
+    Resolution {
+        h: (input.h as f64 * ratio).round() as u32,
+        v: (input.v as f64 * ratio).round() as u32,
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ExtronResponse {
+    Unknown,
+    Reconfig,
+    ActivePixels(u32),
+    ActiveLines(u32),
+    InputHSizeSet,
+    InputVSizeSet,
+    HorizontalCenter,
+    VertialCenter,
+}
+
+/// Drives the state machine against `transport`. Idles waiting for an
+/// unsolicited push from the device (e.g. `Reconfig`), then runs the
+/// resulting command chain to completion using reliable request/response
+/// matching before idling again. Returns on the first unrecoverable error.
+pub fn run(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    loop {
+        drive_once(transport, state, protocol, opts)?;
+    }
+}
+
+/// Waits for a single unsolicited push from the device and, if one arrives,
+/// drives the resulting command chain to completion. If the idle wait times
+/// out, runs the keepalive poll (`poll_for_reconfig`) instead and drives the
+/// chain only if that poll kicked the state machine into `Reconfig`;
+/// otherwise returns without doing anything. Split out of `run`
+/// so the drive logic can be exercised one step at a time in tests.
+fn drive_once(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    match transport
+        .read_line(opts.idle_timeout)
+        .map_err(CommandError::Io)?
+    {
+        Some(line) => {
+            let response = protocol.decode(&line);
+            println!("Extron response: {:?}", response);
+            update_state(response, state);
+            println!("State -> {}", state);
+        }
+        None => {
+            if !poll_for_reconfig(transport, state, protocol, opts)? {
+                return Ok(());
+            }
+        }
+    }
+
+    while let Some(cmd) = protocol.request_for(state) {
+        let expect = protocol
+            .expected_response_for(&state.step)
+            .expect("request_for only returns Some for steps with a known expected response");
+        println!("Sending command: {}", cmd);
+        let response = send_command_with_response(
+            transport,
+            &cmd,
+            protocol,
+            expect,
+            opts.response_timeout,
+            opts.max_retries,
+        )?;
+        update_state(response, state);
+        println!("State -> {}", state);
+    }
+    Ok(())
+}
+
+/// Asks `protocol` what command it would send for `step`, without disturbing
+/// the real `state`'s step. Used by the idle poll, which needs the
+/// `Reconfig`/`GotHorizontalSize` commands outside of their normal place in
+/// the flow.
+fn probe_request(protocol: &dyn ScalerProtocol, state: &State, step: CommandFlow) -> Option<String> {
+    let probe = State {
+        step,
+        input_size: state.input_size,
+        output_size: state.output_size,
+        center_base: state.center_base,
+        h_offset: state.h_offset,
+        v_offset: state.v_offset,
+        scale_mode: state.scale_mode,
+        last_poll: None,
+    };
+    protocol.request_for(&probe)
+}
+
+/// Like `probe_request`, but for the REPL's manual `hsiz <n>` command, which
+/// sends an operator-chosen absolute size rather than `state`'s computed one.
+/// Forces `ScaleMode::Center` (a no-op passthrough) with `input_size.h = n`
+/// so `protocol.request_for` emits exactly `n` instead of a scaled value.
+fn probe_raw_hsize_request(protocol: &dyn ScalerProtocol, state: &State, n: u32) -> Option<String> {
+    let probe = State {
+        step: CommandFlow::GotVerticalSize,
+        input_size: Resolution { h: n, v: 0 },
+        output_size: state.output_size,
+        center_base: state.center_base,
+        h_offset: state.h_offset,
+        v_offset: state.v_offset,
+        scale_mode: ScaleMode::Center,
+        last_poll: None,
+    };
+    protocol.request_for(&probe)
+}
+
+/// While idle, periodically re-reads `APIX`/`ALIN` and compares them against
+/// `state.input_size`: some sources change resolution without emitting a
+/// `Reconfig`, so this is the only way to notice. Returns `Ok(true)` if a
+/// change was found and the state machine was kicked back into the
+/// `Reconfig` step.
+fn poll_for_reconfig(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<bool, CommandError> {
+    let interval = match opts.poll_interval {
+        Some(interval) => interval,
+        None => return Ok(false),
+    };
+    if !matches!(state.step, CommandFlow::Uninitialized) {
+        return Ok(false);
+    }
+    let due = match state.last_poll {
+        Some(last) => last.elapsed() >= interval,
+        None => true,
+    };
+    if !due {
+        return Ok(false);
+    }
+    state.last_poll = Some(Instant::now());
+
+    let apix_cmd = probe_request(protocol, state, CommandFlow::Reconfig)
+        .expect("every protocol must have a command for CommandFlow::Reconfig");
+    let alin_cmd = probe_request(protocol, state, CommandFlow::GotHorizontalSize)
+        .expect("every protocol must have a command for CommandFlow::GotHorizontalSize");
+    let is_pixels = protocol
+        .expected_response_for(&CommandFlow::Reconfig)
+        .expect("every protocol must expect a response for CommandFlow::Reconfig");
+    let is_lines = protocol
+        .expected_response_for(&CommandFlow::GotHorizontalSize)
+        .expect("every protocol must expect a response for CommandFlow::GotHorizontalSize");
+
+    let pixels = send_command_with_response(
+        transport,
+        &apix_cmd,
+        protocol,
+        is_pixels,
+        opts.response_timeout,
+        opts.max_retries,
+    )?;
+    if pixels == ExtronResponse::Reconfig {
+        update_state(pixels, state);
+        return Ok(true);
+    }
+    let lines = send_command_with_response(
+        transport,
+        &alin_cmd,
+        protocol,
+        is_lines,
+        opts.response_timeout,
+        opts.max_retries,
+    )?;
+    if lines == ExtronResponse::Reconfig {
+        update_state(lines, state);
+        return Ok(true);
+    }
+    let (h, v) = match (pixels, lines) {
+        (ExtronResponse::ActivePixels(h), ExtronResponse::ActiveLines(v)) => (h, v),
+        _ => unreachable!(
+            "send_command_with_response only returns responses matching `expect`, or Reconfig"
+        ),
+    };
+
+    if h == state.input_size.h && v == state.input_size.v {
+        return Ok(false);
+    }
+
+    println!(
+        "Poll detected input size change: {}x{} -> {}x{}, reconfiguring",
+        state.input_size.h, state.input_size.v, h, v
+    );
+    state.reset();
+    state.step = CommandFlow::Reconfig;
+    Ok(true)
+}
+
+/// A diagnostic REPL for commissioning a scaler by hand. Reads commands from
+/// stdin and drives `transport`/`state` through the same `protocol` and
+/// `update_state` the automatic `run` loop uses, so manual and automatic
+/// paths stay consistent. Supported commands:
+///
+/// - `apix` / `alin` — request the active pixel/line count
+/// - `hsiz <n>` — set the scaled horizontal size to `n`
+/// - `state` — print the current `State`
+/// - `raw <bytes>` — send `ESC` followed by `<bytes>` verbatim
+/// - `step` — advance the protocol's command chain exactly once
+/// - `quit` / `exit` — leave the REPL
+pub fn run_interactive(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    let stdin = io::stdin();
+    loop {
+        print!("esc> ");
+        io::stdout().flush().map_err(CommandError::Io)?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).map_err(CommandError::Io)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or("");
+        let arg = parts.next();
+
+        let result = match command {
+            "apix" => dispatch(
+                transport,
+                state,
+                protocol,
+                &probe_request(protocol, state, CommandFlow::Reconfig)
+                    .expect("every protocol must have a command for CommandFlow::Reconfig"),
+                protocol
+                    .expected_response_for(&CommandFlow::Reconfig)
+                    .expect("the protocol must expect a response to an active-pixels query"),
+                opts,
+            ),
+            "alin" => dispatch(
+                transport,
+                state,
+                protocol,
+                &probe_request(protocol, state, CommandFlow::GotHorizontalSize)
+                    .expect("every protocol must have a command for CommandFlow::GotHorizontalSize"),
+                protocol
+                    .expected_response_for(&CommandFlow::GotHorizontalSize)
+                    .expect("the protocol must expect a response to an active-lines query"),
+                opts,
+            ),
+            "hsiz" => match arg.and_then(|v| v.parse::<u32>().ok()) {
+                Some(n) => dispatch(
+                    transport,
+                    state,
+                    protocol,
+                    &probe_raw_hsize_request(protocol, state, n)
+                        .expect("every protocol must have a command for CommandFlow::GotVerticalSize"),
+                    protocol
+                        .expected_response_for(&CommandFlow::GotVerticalSize)
+                        .expect("the protocol must expect a response to an HSIZ set"),
+                    opts,
+                ),
+                None => {
+                    eprintln!("Usage: hsiz <n>");
+                    Ok(())
+                }
+            },
+            "state" => {
+                println!("{}", state);
+                Ok(())
+            }
+            "raw" => match arg {
+                Some(bytes) => send_raw(transport, bytes, protocol, opts),
+                None => {
+                    eprintln!("Usage: raw <bytes>");
+                    Ok(())
+                }
+            },
+            "step" => step_once(transport, state, protocol, opts),
+            "quit" | "exit" => return Ok(()),
+            other => {
+                eprintln!("Unknown command: {:?}", other);
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+        }
+    }
+}
+
+fn dispatch(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    cmd: &str,
+    expect: fn(&ExtronResponse) -> bool,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    let response = send_command_with_response(
+        transport,
+        cmd,
+        protocol,
+        expect,
+        opts.response_timeout,
+        opts.max_retries,
+    )?;
+    update_state(response, state);
+    println!("State -> {}", state);
+    Ok(())
+}
+
+fn send_raw(
+    transport: &mut dyn ScalerTransport,
+    bytes: &str,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    let cmd = format!("{}{}", ESC, bytes);
+    transport.write_command(&cmd).map_err(CommandError::Io)?;
+    match transport
+        .read_line(opts.response_timeout)
+        .map_err(CommandError::Io)?
+    {
+        Some(line) => println!("Extron response: {:?}", protocol.decode(&line)),
+        None => println!("No response received"),
+    }
+    Ok(())
+}
+
+fn step_once(
+    transport: &mut dyn ScalerTransport,
+    state: &mut State,
+    protocol: &dyn ScalerProtocol,
+    opts: &LinkOptions,
+) -> Result<(), CommandError> {
+    match protocol.request_for(state) {
+        Some(cmd) => {
+            let expect = protocol
+                .expected_response_for(&state.step)
+                .expect("request_for only returns Some for steps with a known expected response");
+            println!("Sending command: {}", cmd);
+            let response = send_command_with_response(
+                transport,
+                &cmd,
+                protocol,
+                expect,
+                opts.response_timeout,
+                opts.max_retries,
+            )?;
+            update_state(response, state);
+            println!("State -> {}", state);
+        }
+        None => println!("Nothing to do at step {:?}", state.step),
+    }
+    Ok(())
+}
+
+/// Writes `cmd` and waits for a reply satisfying `expect`, discarding any
+/// unrelated lines in the meantime. Retries up to `retries` times (writing
+/// `cmd` again each time) before giving up. An unsolicited `Reconfig` is
+/// never just "unrelated" -- it means the input changed out from under the
+/// in-flight chain, so it's returned immediately (even though it doesn't
+/// satisfy `expect`) instead of being discarded, letting the caller abort
+/// and restart the chain from `update_state`.
+pub fn send_command_with_response(
+    transport: &mut dyn ScalerTransport,
+    cmd: &str,
+    protocol: &dyn ScalerProtocol,
+    expect: fn(&ExtronResponse) -> bool,
+    timeout: Duration,
+    retries: u32,
+) -> Result<ExtronResponse, CommandError> {
+    for attempt in 0..=retries {
+        transport.write_command(cmd).map_err(CommandError::Io)?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            match transport.read_line(remaining).map_err(CommandError::Io)? {
+                Some(line) => {
+                    let response = protocol.decode(&line);
+                    println!("Extron response: {:?}", response);
+                    if expect(&response) || response == ExtronResponse::Reconfig {
+                        return Ok(response);
+                    }
+                }
+                None => break,
+            }
+        }
+
+        eprintln!(
+            "Timed out waiting for a response to {:?} (attempt {}/{})",
+            cmd,
+            attempt + 1,
+            retries + 1
+        );
+    }
+    Err(CommandError::Timeout(cmd.to_string()))
+}
+
+pub fn update_state(response: ExtronResponse, state: &mut State) {
+    match response {
+        ExtronResponse::Unknown => {
+            // Do nothing, sometimes the scaler sends Img and other bits that we don't care about
+        }
+        ExtronResponse::Reconfig => {
+            state.reset();
+            state.step = CommandFlow::Reconfig;
+        }
+        ExtronResponse::ActivePixels(h) => {
+            state.input_size.h = h;
+            state.step = CommandFlow::GotHorizontalSize
+        }
+        ExtronResponse::ActiveLines(v) => {
+            state.input_size.v = v;
+            state.step = CommandFlow::GotVerticalSize
+        }
+        ExtronResponse::InputHSizeSet => state.step = CommandFlow::SetHSize,
+        ExtronResponse::InputVSizeSet => state.step = CommandFlow::SetVSize,
+        ExtronResponse::HorizontalCenter => state.step = CommandFlow::SetHCenter,
+        ExtronResponse::VertialCenter => state.step = CommandFlow::Uninitialized,
+    }
+}
+
+/// Computes an `HCTR`/`VCTR` value that centers `input` within `output`
+/// relative to `base`, plus a fixed nudge. Clamped to never go negative.
+/// Shared scaling/centering math, kept here (rather than in `protocol`)
+/// since it's independent of any one command dialect.
+pub(crate) fn centered_offset(base: u32, output: u32, input: u32, offset: i32) -> u32 {
+    let centered = base as i64 + (output as i64 / 2 - input as i64 / 2) + offset as i64;
+    centered.max(0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ExtronSisProtocol;
+    use std::collections::VecDeque;
+
+    /// Feeds canned response lines and records every command the state
+    /// machine writes back, so the drive loop can be exercised without a
+    /// real serial port.
+    struct MockTransport {
+        incoming: VecDeque<Vec<u8>>,
+        pub sent: Vec<String>,
+    }
+
+    impl MockTransport {
+        fn new(lines: &[&str]) -> Self {
+            Self {
+                incoming: lines.iter().map(|l| l.as_bytes().to_vec()).collect(),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl ScalerTransport for MockTransport {
+        fn read_line(&mut self, _timeout: Duration) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.incoming.pop_front())
+        }
+
+        fn write_command(&mut self, cmd: &str) -> io::Result<()> {
+            self.sent.push(cmd.to_string());
+            Ok(())
+        }
+    }
+
+    /// Drives `drive_once` until the scripted transport runs dry, mirroring
+    /// what `run` does without looping forever on an always-idle mock.
+    fn run_until_idle(
+        transport: &mut MockTransport,
+        state: &mut State,
+        protocol: &dyn ScalerProtocol,
+        opts: &LinkOptions,
+    ) {
+        while !transport.incoming.is_empty() {
+            drive_once(transport, state, protocol, opts).expect("unexpected command error");
+        }
+    }
+
+    #[test]
+    fn reconfig_queries_active_pixels_then_lines() {
+        // `drive_once` doesn't hand control back until the whole command
+        // chain it kicks off is acked, so the mock must supply a reply to
+        // every command in the chain, not just the `APIX`/`ALIN` pair this
+        // test cares about — otherwise the trailing `HSIZ` send blocks on a
+        // reply that never arrives and times out.
+        let mut transport = MockTransport::new(&[
+            "Reconfig", "Apix1920", "Alin1080", "Hsiz", "Vsiz", "Hctr", "Vctr",
+        ]);
+        let mut state = State::default();
+        state.set_output_size(Resolution { h: 1920, v: 1080 });
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(1, None);
+
+        run_until_idle(&mut transport, &mut state, &protocol, &opts);
+
+        assert_eq!(
+            &transport.sent[..2],
+            [format!("{}APIX\r", ESC), format!("{}ALIN\r", ESC)]
+        );
+        assert_eq!(state.input_size.h, 1920);
+        assert_eq!(state.input_size.v, 1080);
+    }
+
+    #[test]
+    fn full_handshake_centers_on_matching_output() {
+        let mut transport = MockTransport::new(&[
+            "Reconfig", "Apix1920", "Alin1080", "Hsiz", "Vsiz", "Hctr", "Vctr",
+        ]);
+        let mut state = State::default();
+        state.set_output_size(Resolution { h: 1920, v: 1080 });
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(1, None);
+
+        run_until_idle(&mut transport, &mut state, &protocol, &opts);
+
+        assert_eq!(
+            transport.sent,
+            vec![
+                format!("{}APIX\r", ESC),
+                format!("{}ALIN\r", ESC),
+                format!("{}1920HSIZ\r", ESC),
+                format!("{}1080VSIZ\r", ESC),
+                format!("{}10240HCTR\r", ESC),
+                format!("{}10240VCTR\r", ESC),
+            ]
+        );
+    }
+
+    #[test]
+    fn reconfig_mid_chain_restarts_instead_of_being_discarded() {
+        // A second `Reconfig` lands while `APIX` is still pending (the
+        // source changed again mid-handshake). It must abort the in-flight
+        // chain and restart from `APIX`, not be swallowed as an unrelated
+        // line like `unrelated_response_does_not_desync_pending_command`.
+        let mut transport = MockTransport::new(&[
+            "Reconfig", "Reconfig", "Apix1920", "Alin1080", "Hsiz", "Vsiz", "Hctr", "Vctr",
+        ]);
+        let mut state = State::default();
+        state.set_output_size(Resolution { h: 1920, v: 1080 });
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(1, None);
+
+        run_until_idle(&mut transport, &mut state, &protocol, &opts);
+
+        assert_eq!(
+            transport.sent,
+            vec![
+                format!("{}APIX\r", ESC),
+                format!("{}APIX\r", ESC),
+                format!("{}ALIN\r", ESC),
+                format!("{}1920HSIZ\r", ESC),
+                format!("{}1080VSIZ\r", ESC),
+                format!("{}10240HCTR\r", ESC),
+                format!("{}10240VCTR\r", ESC),
+            ]
+        );
+        assert_eq!(state.input_size.h, 1920);
+        assert_eq!(state.input_size.v, 1080);
+    }
+
+    #[test]
+    fn unrelated_response_does_not_desync_pending_command() {
+        // An unrelated `Apix` arrives while `HSIZ` is still pending: it must
+        // be discarded rather than treated as the `HSIZ` acknowledgment.
+        let mut transport = MockTransport::new(&["Apix1920", "Hsiz"]);
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(0, None);
+
+        let response = send_command_with_response(
+            &mut transport,
+            &format!("{}1920HSIZ\r", ESC),
+            &protocol,
+            protocol.expected_response_for(&CommandFlow::GotVerticalSize).unwrap(),
+            opts.response_timeout,
+            opts.max_retries,
+        );
+
+        assert_eq!(response.unwrap(), ExtronResponse::InputHSizeSet);
+    }
+
+    #[test]
+    fn timeout_surfaces_after_exhausting_retries() {
+        let mut transport = MockTransport::new(&[]);
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(2, None);
+
+        let response = send_command_with_response(
+            &mut transport,
+            &format!("{}APIX\r", ESC),
+            &protocol,
+            protocol.expected_response_for(&CommandFlow::Reconfig).unwrap(),
+            Duration::from_millis(0),
+            opts.max_retries,
+        );
+
+        assert!(matches!(response, Err(CommandError::Timeout(_))));
+        assert_eq!(transport.sent.len(), 3);
+    }
+
+    #[test]
+    fn idle_poll_detects_resolution_change_and_triggers_reconfig() {
+        let mut transport = MockTransport::new(&["Apix2560", "Alin1440"]);
+        let mut state = State {
+            input_size: Resolution { h: 1920, v: 1080 },
+            ..Default::default()
+        };
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(0, Some(Duration::from_millis(0)));
+
+        let triggered = poll_for_reconfig(&mut transport, &mut state, &protocol, &opts).unwrap();
+
+        assert!(triggered);
+        assert!(matches!(state.step, CommandFlow::Reconfig));
+        assert_eq!(
+            transport.sent,
+            vec![format!("{}APIX\r", ESC), format!("{}ALIN\r", ESC)]
+        );
+    }
+
+    #[test]
+    fn idle_poll_does_nothing_when_resolution_unchanged() {
+        let mut transport = MockTransport::new(&["Apix1920", "Alin1080"]);
+        let mut state = State {
+            input_size: Resolution { h: 1920, v: 1080 },
+            ..Default::default()
+        };
+        let protocol = ExtronSisProtocol;
+        let opts = LinkOptions::new(0, Some(Duration::from_millis(0)));
+
+        let triggered = poll_for_reconfig(&mut transport, &mut state, &protocol, &opts).unwrap();
+
+        assert!(!triggered);
+        assert!(matches!(state.step, CommandFlow::Uninitialized));
+    }
+
+    #[test]
+    fn fit_mode_letterboxes_to_the_narrower_ratio() {
+        let output = Resolution { h: 1920, v: 1080 };
+        let input = Resolution { h: 1024, v: 768 };
+        let scaled = scaled_size(ScaleMode::Fit, output, input);
+        // min(1920/1024, 1080/768) = min(1.875, 1.40625) = 1.40625
+        assert_eq!(scaled.h, 1440);
+        assert_eq!(scaled.v, 1080);
+    }
+
+    #[test]
+    fn fill_mode_covers_using_the_wider_ratio() {
+        let output = Resolution { h: 1920, v: 1080 };
+        let input = Resolution { h: 1024, v: 768 };
+        let scaled = scaled_size(ScaleMode::Fill, output, input);
+        // max(1920/1024, 1080/768) = max(1.875, 1.40625) = 1.875
+        assert_eq!(scaled.h, 1920);
+        assert_eq!(scaled.v, 1440);
+    }
+
+    #[test]
+    fn center_mode_leaves_input_untouched() {
+        let output = Resolution { h: 1920, v: 1080 };
+        let input = Resolution { h: 1024, v: 768 };
+        let scaled = scaled_size(ScaleMode::Center, output, input);
+        assert_eq!(scaled.h, input.h);
+        assert_eq!(scaled.v, input.v);
+    }
+
+    #[test]
+    fn centered_offset_never_goes_negative_for_an_oversized_fill() {
+        // An input much larger than the output after filling would otherwise
+        // drive the offset negative: 10240 + (100/2 - 50000/2) < 0.
+        let offset = centered_offset(DEFAULT_CENTER_BASE, 100, 50000, 0);
+        assert_eq!(offset, 0);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{centered_offset, scaled_size, CommandFlow, ExtronResponse, State, ESC};
+
+/// Maps the abstract `CommandFlow` steps to the command text and response
+/// parsing of a specific scaler's command set, so `run`/`run_interactive`
+/// don't need to know which dialect they're speaking. Different Extron
+/// families (or firmware revisions with their own centering base) can each
+/// get their own implementation without touching the state machine.
+pub trait ScalerProtocol {
+    /// The command to send for `state.step`, if that step has one.
+    fn request_for(&self, state: &State) -> Option<String>;
+
+    /// Parses a raw response line into a structured `ExtronResponse`.
+    fn decode(&self, line: &[u8]) -> ExtronResponse;
+
+    /// The predicate a reply must satisfy to be treated as the answer to
+    /// the request issued while in `step`.
+    fn expected_response_for(&self, step: &CommandFlow) -> Option<fn(&ExtronResponse) -> bool>;
+}
+
+/// The Extron SIS command set (`APIX`/`ALIN`/`HSIZ`/`VSIZ`/`HCTR`/`VCTR`)
+/// this crate has always spoken; kept as the default so existing
+/// deployments don't need a `--model` to keep working.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ExtronSisProtocol;
+
+impl ScalerProtocol for ExtronSisProtocol {
+    fn request_for(&self, state: &State) -> Option<String> {
+        match state.step {
+            CommandFlow::Reconfig => {
+                // Get active pixels (Width)
+                Some(format!("{}APIX\r", ESC))
+            }
+            CommandFlow::GotHorizontalSize => {
+                // Get active lines (Height)
+                Some(format!("{}ALIN\r", ESC))
+            }
+            CommandFlow::GotVerticalSize => {
+                // Now that we have Width + Height,
+                // Set the scaled horizontal size
+                let scaled = scaled_size(state.scale_mode, state.output_size, state.input_size);
+                Some(format!("{}{}HSIZ\r", ESC, scaled.h))
+            }
+            CommandFlow::SetHSize => {
+                // Set the scaled vertical size
+                let scaled = scaled_size(state.scale_mode, state.output_size, state.input_size);
+                Some(format!("{}{}VSIZ\r", ESC, scaled.v))
+            }
+            CommandFlow::SetVSize => {
+                // Center horizontally
+                let scaled = scaled_size(state.scale_mode, state.output_size, state.input_size);
+                let h = centered_offset(state.center_base, state.output_size.h, scaled.h, state.h_offset);
+                Some(format!("{}{}HCTR\r", ESC, h))
+            }
+            CommandFlow::SetHCenter => {
+                // Center vertically
+                let scaled = scaled_size(state.scale_mode, state.output_size, state.input_size);
+                let v = centered_offset(state.center_base, state.output_size.v, scaled.v, state.v_offset);
+                Some(format!("{}{}VCTR\r", ESC, v))
+            }
+            CommandFlow::Uninitialized => None,
+        }
+    }
+
+    fn decode(&self, line: &[u8]) -> ExtronResponse {
+        if let Ok(command) = String::from_utf8(line.to_vec()) {
+            println!("Decoding response: {}", command);
+            if command.len() < 4 {
+                return ExtronResponse::Unknown;
+            }
+            if command == "Reconfig" {
+                ExtronResponse::Reconfig
+            } else {
+                match &command[0..=3] {
+                    "Apix" => {
+                        if let Ok(pixels) = {
+                            if command.len() > 4 {
+                                command[4..].parse::<u32>().map_err(|_| ())
+                            } else {
+                                Err(())
+                            }
+                        } {
+                            ExtronResponse::ActivePixels(pixels)
+                        } else {
+                            eprintln!("Could not decode active horizontal pixels");
+                            ExtronResponse::Unknown
+                        }
+                    }
+                    "Alin" => {
+                        if let Ok(pixels) = {
+                            if command.len() > 4 {
+                                command[4..].parse::<u32>().map_err(|_| ())
+                            } else {
+                                Err(())
+                            }
+                        } {
+                            ExtronResponse::ActiveLines(pixels)
+                        } else {
+                            eprintln!("Could not decode active vertical lines");
+                            ExtronResponse::Unknown
+                        }
+                    }
+                    "Hsiz" => ExtronResponse::InputHSizeSet,
+                    "Vsiz" => ExtronResponse::InputVSizeSet,
+                    "Hctr" => ExtronResponse::HorizontalCenter,
+                    "Vctr" => ExtronResponse::VertialCenter,
+                    _ => {
+                        eprintln!("Could not decode message");
+                        ExtronResponse::Unknown
+                    }
+                }
+            }
+        } else {
+            eprintln!("Could not decode message");
+            ExtronResponse::Unknown
+        }
+    }
+
+    fn expected_response_for(&self, step: &CommandFlow) -> Option<fn(&ExtronResponse) -> bool> {
+        match step {
+            CommandFlow::Reconfig => Some(is_active_pixels),
+            CommandFlow::GotHorizontalSize => Some(is_active_lines),
+            CommandFlow::GotVerticalSize => Some(is_input_h_size_set),
+            CommandFlow::SetHSize => Some(is_input_v_size_set),
+            CommandFlow::SetVSize => Some(is_horizontal_center),
+            CommandFlow::SetHCenter => Some(is_vertical_center),
+            CommandFlow::Uninitialized => None,
+        }
+    }
+}
+
+fn is_active_pixels(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::ActivePixels(_))
+}
+
+fn is_active_lines(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::ActiveLines(_))
+}
+
+fn is_input_h_size_set(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::InputHSizeSet)
+}
+
+fn is_input_v_size_set(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::InputVSizeSet)
+}
+
+fn is_horizontal_center(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::HorizontalCenter)
+}
+
+fn is_vertical_center(r: &ExtronResponse) -> bool {
+    matches!(r, ExtronResponse::VertialCenter)
+}
+
+/// Resolves the `--model` CLI argument to a concrete `ScalerProtocol`.
+pub fn protocol_for(model: &str) -> Result<Box<dyn ScalerProtocol>, String> {
+    match model {
+        "extron-sis" => Ok(Box::new(ExtronSisProtocol)),
+        other => Err(format!(
+            "Unknown scaler model '{}' (supported: extron-sis)",
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Resolution, ESC};
+
+    #[test]
+    fn request_for_walks_the_full_command_chain() {
+        let protocol = ExtronSisProtocol;
+        let mut state = State::default();
+        state.set_output_size(Resolution { h: 1920, v: 1080 });
+        state.step = CommandFlow::Reconfig;
+
+        assert_eq!(protocol.request_for(&state), Some(format!("{}APIX\r", ESC)));
+
+        state.step = CommandFlow::GotHorizontalSize;
+        assert_eq!(protocol.request_for(&state), Some(format!("{}ALIN\r", ESC)));
+
+        state.input_size = Resolution { h: 1280, v: 720 };
+        state.step = CommandFlow::GotVerticalSize;
+        assert_eq!(protocol.request_for(&state), Some(format!("{}1280HSIZ\r", ESC)));
+
+        state.step = CommandFlow::SetHSize;
+        assert_eq!(protocol.request_for(&state), Some(format!("{}720VSIZ\r", ESC)));
+
+        // 10240 + (1920/2 - 1280/2) and 10240 + (1080/2 - 720/2)
+        state.step = CommandFlow::SetVSize;
+        assert_eq!(protocol.request_for(&state), Some(format!("{}10560HCTR\r", ESC)));
+
+        state.step = CommandFlow::SetHCenter;
+        assert_eq!(protocol.request_for(&state), Some(format!("{}10420VCTR\r", ESC)));
+
+        state.step = CommandFlow::Uninitialized;
+        assert_eq!(protocol.request_for(&state), None);
+    }
+
+    #[test]
+    fn decode_parses_known_prefixes_and_ignores_the_rest() {
+        let protocol = ExtronSisProtocol;
+        assert_eq!(protocol.decode(b"Apix1920"), ExtronResponse::ActivePixels(1920));
+        assert_eq!(protocol.decode(b"Alin1080"), ExtronResponse::ActiveLines(1080));
+        assert_eq!(protocol.decode(b"Reconfig"), ExtronResponse::Reconfig);
+        assert_eq!(protocol.decode(b"Img"), ExtronResponse::Unknown);
+    }
+
+    #[test]
+    fn expected_response_for_matches_the_command_it_was_sent_for() {
+        let protocol = ExtronSisProtocol;
+        assert!(protocol
+            .expected_response_for(&CommandFlow::Reconfig)
+            .unwrap()(&ExtronResponse::ActivePixels(1920)));
+        assert!(!protocol
+            .expected_response_for(&CommandFlow::Reconfig)
+            .unwrap()(&ExtronResponse::ActiveLines(1080)));
+        assert!(protocol.expected_response_for(&CommandFlow::Uninitialized).is_none());
+    }
+
+    #[test]
+    fn protocol_for_rejects_unknown_models() {
+        assert!(protocol_for("extron-sis").is_ok());
+        assert!(protocol_for("some-other-scaler").is_err());
+    }
+}